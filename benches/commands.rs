@@ -0,0 +1,34 @@
+use std::hint::black_box;
+
+use criterion::{Criterion, criterion_group, criterion_main};
+
+use mcmc_rs::PreparedCommand;
+
+/// Building commands only, no server needed.
+fn criterion_benchmark(c: &mut Criterion) {
+    let value = vec![0u8; 4096];
+
+    c.bench_function("build set", |b| {
+        b.iter(|| PreparedCommand::set(black_box(b"key0"), 0, 0, false, black_box(b"value")))
+    });
+
+    c.bench_function("build set 4KB", |b| {
+        b.iter(|| PreparedCommand::set(black_box(b"key0"), 0, 0, false, black_box(&value)))
+    });
+
+    c.bench_function("build cas max numbers", |b| {
+        b.iter(|| {
+            PreparedCommand::cas(
+                black_box(b"key0"),
+                u32::MAX,
+                i64::MIN,
+                u64::MAX,
+                true,
+                black_box(b"value"),
+            )
+        })
+    });
+}
+
+criterion_group!(benches, criterion_benchmark);
+criterion_main!(benches);
@@ -40,9 +40,46 @@ fn criterion_benchmark(c: &mut Criterion) {
             b.iter(|| block_on(async { conn.get(black_box(b"key0")).await.unwrap() }))
         });
 
+        c.bench_function(&format!("{name}->set noreply"), |b| {
+            b.iter(|| {
+                block_on(async {
+                    conn.set(black_box(b"key0"), 0, 0, true, black_box(b"value"))
+                        .await
+                        .unwrap()
+                })
+            })
+        });
+
         c.bench_function(&format!("{name}->version"), |b| {
             b.iter(|| block_on(async { conn.version().await.unwrap() }))
         });
+
+        if name != "udp" {
+            let values = vec![vec![0u8; 65536]; 1000];
+            c.bench_function(&format!("{name}->pipeline set 1000x64KB"), |b| {
+                b.iter(|| {
+                    block_on(async {
+                        let mut pipeline = conn.pipeline();
+                        for (i, value) in values.iter().enumerate() {
+                            pipeline = pipeline.set(format!("key{i}"), 0, 0, false, value);
+                        }
+                        pipeline.execute().await.unwrap()
+                    })
+                })
+            });
+
+            c.bench_function(&format!("{name}->pipeline set_borrowed 1000x64KB"), |b| {
+                b.iter(|| {
+                    block_on(async {
+                        let mut pipeline = conn.pipeline();
+                        for (i, value) in values.iter().enumerate() {
+                            pipeline = pipeline.set_borrowed(format!("key{i}"), 0, 0, false, value);
+                        }
+                        pipeline.execute().await.unwrap()
+                    })
+                })
+            });
+        }
     }
 }
 
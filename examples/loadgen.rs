@@ -0,0 +1,250 @@
+//! Load generator for memcached nodes.
+//!
+//! ```bash
+//! cargo run --release --example loadgen --features bench-util -- \
+//!     --addr 127.0.0.1:11211 --keys 10000 --value-size 100 \
+//!     --concurrency 16 --duration 10 --set-ratio 0.1 --noreply-ratio 0.5 --batch 16
+//! ```
+
+use std::env;
+use std::process;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use crc32fast::hash as crc32;
+use smol::{Timer, block_on, io};
+
+use mcmc_rs::bench_util::{LatencyRecorder, Shutdown};
+use mcmc_rs::{AddrArg, ClientCrc32, Connection, Manager, Pool};
+
+struct Config {
+    addrs: Vec<&'static str>,
+    keys: u64,
+    value_size: usize,
+    concurrency: usize,
+    duration: Duration,
+    set_ratio: f64,
+    noreply_ratio: f64,
+    batch: usize,
+}
+
+const USAGE: &str = "usage: loadgen [--addr HOST:PORT]... [--keys N] [--value-size BYTES] \
+[--concurrency N] [--duration SECS] [--set-ratio 0..1] [--noreply-ratio 0..1] [--batch N]";
+
+fn usage_error(msg: &str) -> ! {
+    eprintln!("{msg}\n{USAGE}");
+    process::exit(2)
+}
+
+fn parse<T: FromStr>(name: &str, value: &str) -> T {
+    value
+        .parse()
+        .unwrap_or_else(|_| usage_error(&format!("invalid value for {name}: {value}")))
+}
+
+impl Config {
+    fn from_args() -> Self {
+        let mut c = Config {
+            addrs: Vec::new(),
+            keys: 10_000,
+            value_size: 100,
+            concurrency: 16,
+            duration: Duration::from_secs(10),
+            set_ratio: 0.1,
+            noreply_ratio: 0.0,
+            batch: 16,
+        };
+        let mut args = env::args().skip(1);
+        while let Some(name) = args.next() {
+            let value = args
+                .next()
+                .unwrap_or_else(|| usage_error(&format!("missing value for {name}")));
+            match name.as_str() {
+                "--addr" => c.addrs.push(value.leak()),
+                "--keys" => c.keys = parse(&name, &value),
+                "--value-size" => c.value_size = parse(&name, &value),
+                "--concurrency" => c.concurrency = parse(&name, &value),
+                "--duration" => c.duration = Duration::from_secs(parse(&name, &value)),
+                "--set-ratio" => c.set_ratio = parse(&name, &value),
+                "--noreply-ratio" => c.noreply_ratio = parse(&name, &value),
+                "--batch" => c.batch = parse(&name, &value),
+                other => usage_error(&format!("unknown argument {other}")),
+            }
+        }
+        if c.addrs.is_empty() {
+            c.addrs.push("127.0.0.1:11211")
+        }
+        for (name, value) in [
+            ("--keys", c.keys as usize),
+            ("--concurrency", c.concurrency),
+            ("--batch", c.batch),
+        ] {
+            if value == 0 {
+                usage_error(&format!("{name} must be at least 1"))
+            }
+        }
+        for (name, value) in [
+            ("--set-ratio", c.set_ratio),
+            ("--noreply-ratio", c.noreply_ratio),
+        ] {
+            if !(0.0..=1.0).contains(&value) {
+                usage_error(&format!("{name} must be between 0 and 1"))
+            }
+        }
+        c
+    }
+}
+
+// xorshift64, good enough to pick keys and operations.
+struct Rng(u64);
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn ratio(&mut self) -> f64 {
+        (self.next() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+struct WorkerReport {
+    ops: u64,
+    errors: u64,
+    latency: LatencyRecorder,
+}
+
+struct Op {
+    key: String,
+    set: bool,
+    noreply: bool,
+}
+
+// Keys go to the node ClientCrc32 preloaded them on, so every batch is
+// split into one pipeline per node.
+async fn worker(
+    id: usize,
+    pools: Vec<Pool<'static>>,
+    cfg: &'static Config,
+    shutdown: Shutdown,
+) -> WorkerReport {
+    let mut rng = Rng(0x9E37_79B9_7F4A_7C15 ^ (id as u64 + 1));
+    let value = vec![b'x'; cfg.value_size];
+    let mut report = WorkerReport {
+        ops: 0,
+        errors: 0,
+        latency: LatencyRecorder::new(),
+    };
+    // Backs off while a pool cannot connect, so a down server does not
+    // spin the worker and flood the error count.
+    let mut backoff = Duration::ZERO;
+    while !shutdown.is_triggered() {
+        let mut batches: Vec<Vec<Op>> = pools.iter().map(|_| Vec::new()).collect();
+        for _ in 0..cfg.batch {
+            let key = format!("loadgen:{}", rng.next() % cfg.keys);
+            let node = crc32(key.as_bytes()) as usize % pools.len();
+            let set = rng.ratio() < cfg.set_ratio;
+            let noreply = set && rng.ratio() < cfg.noreply_ratio;
+            batches[node].push(Op { key, set, noreply })
+        }
+        for (pool, ops) in pools.iter().zip(batches) {
+            if ops.is_empty() {
+                continue;
+            }
+            let mut conn = match pool.get().await {
+                Ok(c) => {
+                    backoff = Duration::ZERO;
+                    c
+                }
+                Err(_) => {
+                    report.errors += 1;
+                    backoff =
+                        (backoff * 2).clamp(Duration::from_millis(10), Duration::from_secs(1));
+                    Timer::after(backoff).await;
+                    continue;
+                }
+            };
+            let count = ops.len() as u64;
+            let p = ops.into_iter().fold(conn.pipeline(), |p, op| {
+                if op.set {
+                    p.set(op.key, 0, 0, op.noreply, &value)
+                } else {
+                    p.get(op.key)
+                }
+            });
+            let start = Instant::now();
+            match p.execute().await {
+                Ok(_) => {
+                    report.latency.record(start.elapsed());
+                    report.ops += count
+                }
+                Err(_) => report.errors += 1,
+            }
+        }
+    }
+    report
+}
+
+fn main() -> io::Result<()> {
+    let cfg: &'static Config = Box::leak(Box::new(Config::from_args()));
+    block_on(async {
+        // Preload keys sharded across all nodes.
+        let mut conns = Vec::new();
+        for addr in &cfg.addrs {
+            conns.push(Connection::tcp_connect(addr).await?)
+        }
+        let mut client = ClientCrc32::new(conns);
+        let value = vec![b'x'; cfg.value_size];
+        for i in 0..cfg.keys {
+            client
+                .set(format!("loadgen:{i}"), 0, 0, true, &value)
+                .await?;
+        }
+
+        let pools: Vec<Pool<'static>> = cfg
+            .addrs
+            .iter()
+            .map(|&a| {
+                Pool::builder(Manager::new(AddrArg::Tcp(a)))
+                    .max_size(cfg.concurrency)
+                    .build()
+                    .unwrap()
+            })
+            .collect();
+        let shutdown = Shutdown::after(cfg.duration);
+        let start = Instant::now();
+        let tasks: Vec<_> = (0..cfg.concurrency)
+            .map(|id| smol::spawn(worker(id, pools.clone(), cfg, shutdown.clone())))
+            .collect();
+
+        let (mut ops, mut errors, mut latency) = (0, 0, LatencyRecorder::new());
+        for t in tasks {
+            let r = t.await;
+            ops += r.ops;
+            errors += r.errors;
+            latency.merge(&r.latency);
+        }
+        let elapsed = start.elapsed();
+
+        println!("nodes:       {}", cfg.addrs.join(","));
+        println!("concurrency: {}", cfg.concurrency);
+        println!("batch:       {}", cfg.batch);
+        println!("elapsed:     {elapsed:?}");
+        println!("ops:         {ops}");
+        println!("errors:      {errors}");
+        println!(
+            "throughput:  {:.0} ops/sec",
+            ops as f64 / elapsed.as_secs_f64()
+        );
+        for p in [50.0, 90.0, 99.0, 99.9] {
+            println!(
+                "p{p:<5}       {:?}",
+                latency.percentile(p).unwrap_or_default()
+            );
+        }
+        println!("max:         {:?}", latency.max().unwrap_or_default());
+        Ok(())
+    })
+}
@@ -0,0 +1,214 @@
+//! Small helpers for load generators built on top of this crate.
+//!
+//! - [LatencyRecorder] collects request latencies and reports percentiles.
+//! - [Shutdown] is a cloneable stop signal shared by worker tasks.
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Default, Clone)]
+pub struct LatencyRecorder {
+    samples: Vec<u64>,
+    sorted: bool,
+}
+
+impl LatencyRecorder {
+    /// # Example
+    ///
+    /// ```
+    /// use mcmc_rs::bench_util::LatencyRecorder;
+    ///
+    /// let recorder = LatencyRecorder::new();
+    /// assert!(recorder.is_empty());
+    /// ```
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// # Example
+    ///
+    /// ```
+    /// use std::time::Duration;
+    ///
+    /// use mcmc_rs::bench_util::LatencyRecorder;
+    ///
+    /// let mut recorder = LatencyRecorder::new();
+    /// recorder.record(Duration::from_micros(10));
+    /// assert_eq!(recorder.len(), 1);
+    /// ```
+    pub fn record(&mut self, latency: Duration) {
+        self.samples
+            .push(u64::try_from(latency.as_micros()).unwrap_or(u64::MAX));
+        self.sorted = false;
+    }
+
+    /// # Example
+    ///
+    /// ```
+    /// use std::time::Duration;
+    ///
+    /// use mcmc_rs::bench_util::LatencyRecorder;
+    ///
+    /// let mut a = LatencyRecorder::new();
+    /// let mut b = LatencyRecorder::new();
+    /// a.record(Duration::from_micros(1));
+    /// b.record(Duration::from_micros(2));
+    /// a.merge(&b);
+    /// assert_eq!(a.len(), 2);
+    /// ```
+    pub fn merge(&mut self, other: &LatencyRecorder) {
+        self.samples.extend(&other.samples);
+        self.sorted = false;
+    }
+
+    pub fn len(&self) -> usize {
+        self.samples.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.samples.is_empty()
+    }
+
+    /// Returns the latency below which `p` percent of samples fall,
+    /// `p` is clamped to `0.0..=100.0`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::time::Duration;
+    ///
+    /// use mcmc_rs::bench_util::LatencyRecorder;
+    ///
+    /// let mut recorder = LatencyRecorder::new();
+    /// (1..=100).for_each(|x| recorder.record(Duration::from_micros(x)));
+    /// assert_eq!(recorder.percentile(50.0), Some(Duration::from_micros(50)));
+    /// assert_eq!(recorder.percentile(99.0), Some(Duration::from_micros(99)));
+    /// ```
+    pub fn percentile(&mut self, p: f64) -> Option<Duration> {
+        if self.samples.is_empty() {
+            return None;
+        }
+        if !self.sorted {
+            self.samples.sort_unstable();
+            self.sorted = true;
+        }
+        let p = p.clamp(0.0, 100.0);
+        let rank = ((p / 100.0) * self.samples.len() as f64).ceil() as usize;
+        let index = rank.saturating_sub(1).min(self.samples.len() - 1);
+        Some(Duration::from_micros(self.samples[index]))
+    }
+
+    pub fn mean(&self) -> Option<Duration> {
+        if self.samples.is_empty() {
+            return None;
+        }
+        let sum: u128 = self.samples.iter().map(|&x| x as u128).sum();
+        Some(Duration::from_micros(
+            (sum / self.samples.len() as u128) as u64,
+        ))
+    }
+
+    pub fn max(&self) -> Option<Duration> {
+        self.samples.iter().max().map(|&x| Duration::from_micros(x))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Shutdown {
+    flag: Arc<AtomicBool>,
+    deadline: Option<Instant>,
+}
+
+impl Shutdown {
+    /// # Example
+    ///
+    /// ```
+    /// use mcmc_rs::bench_util::Shutdown;
+    ///
+    /// let shutdown = Shutdown::new();
+    /// let worker = shutdown.clone();
+    /// shutdown.trigger();
+    /// assert!(worker.is_triggered());
+    /// ```
+    pub fn new() -> Self {
+        Self {
+            flag: Arc::new(AtomicBool::new(false)),
+            deadline: None,
+        }
+    }
+
+    /// Signal that triggers by itself once `duration` has elapsed.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::time::Duration;
+    ///
+    /// use mcmc_rs::bench_util::Shutdown;
+    ///
+    /// let shutdown = Shutdown::after(Duration::ZERO);
+    /// assert!(shutdown.is_triggered());
+    /// ```
+    pub fn after(duration: Duration) -> Self {
+        Self {
+            flag: Arc::new(AtomicBool::new(false)),
+            deadline: Instant::now().checked_add(duration),
+        }
+    }
+
+    pub fn trigger(&self) {
+        self.flag.store(true, Ordering::Release)
+    }
+
+    pub fn is_triggered(&self) -> bool {
+        self.flag.load(Ordering::Acquire) || self.deadline.is_some_and(|x| Instant::now() >= x)
+    }
+}
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_latency_recorder() {
+        let mut r = LatencyRecorder::new();
+        assert_eq!(r.percentile(50.0), None);
+        assert_eq!(r.mean(), None);
+
+        [30, 10, 20, 40]
+            .into_iter()
+            .for_each(|x| r.record(Duration::from_micros(x)));
+        assert_eq!(r.percentile(0.0), Some(Duration::from_micros(10)));
+        assert_eq!(r.percentile(50.0), Some(Duration::from_micros(20)));
+        assert_eq!(r.percentile(75.0), Some(Duration::from_micros(30)));
+        assert_eq!(r.percentile(100.0), Some(Duration::from_micros(40)));
+        assert_eq!(r.percentile(150.0), Some(Duration::from_micros(40)));
+        assert_eq!(r.mean(), Some(Duration::from_micros(25)));
+        assert_eq!(r.max(), Some(Duration::from_micros(40)));
+
+        let mut other = LatencyRecorder::new();
+        other.record(Duration::from_micros(1));
+        r.merge(&other);
+        assert_eq!(r.len(), 5);
+        assert_eq!(r.percentile(0.0), Some(Duration::from_micros(1)));
+    }
+
+    #[test]
+    fn test_shutdown() {
+        let s = Shutdown::new();
+        let worker = s.clone();
+        assert!(!worker.is_triggered());
+        s.trigger();
+        assert!(worker.is_triggered());
+
+        assert!(!Shutdown::after(Duration::from_secs(3600)).is_triggered());
+        assert!(Shutdown::after(Duration::ZERO).is_triggered());
+    }
+}
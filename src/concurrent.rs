@@ -0,0 +1,209 @@
+//! Bounded concurrency for operations over many keys.
+//!
+//! Loaders, warmers and migrations apply one operation per key and
+//! want several in flight without flooding the servers. [for_each_key]
+//! checks out up to `concurrency` connections from a [Pool] and runs
+//! the operation on each key, one key per connection at a time.
+//!
+//! [Connection::dump_to_writer] and [Connection::load_from_reader] are
+//! not built on it: they send batches of pipelined commands over one
+//! connection, fewer round trips than one operation per key, and a
+//! metadump is read on the connection that requested it.
+
+use std::future::poll_fn;
+use std::pin::Pin;
+use std::sync::Mutex;
+use std::task::Poll;
+
+use deadpool::managed::Object;
+
+use crate::rt::io;
+use crate::{Connection, Manager, Pool, pool_error};
+
+/// What [for_each_key] does after an operation fails.
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub enum OnError {
+    /// Stops handing out keys and drops operations in flight, their
+    /// connections are poisoned since a reply may still arrive.
+    #[default]
+    Cancel,
+    /// Runs the operation on every key.
+    Continue,
+}
+
+/// Settings of [for_each_key], a `usize` converts into the
+/// concurrency with [OnError::Cancel].
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Options {
+    /// Operations in flight at most, capped by the pool size.
+    pub concurrency: usize,
+    pub on_error: OnError,
+}
+
+impl From<usize> for Options {
+    fn from(concurrency: usize) -> Self {
+        Self {
+            concurrency,
+            on_error: OnError::default(),
+        }
+    }
+}
+
+/// Per key results of [for_each_key].
+#[derive(Debug)]
+pub struct Outcome<K, T> {
+    /// Keys the operation finished on, in input order.
+    pub results: Vec<(K, io::Result<T>)>,
+    /// Keys not run or dropped in flight after a failure with
+    /// [OnError::Cancel], in input order.
+    pub cancelled: Vec<K>,
+}
+
+impl<K, T> Outcome<K, T> {
+    /// The first error in input order, or every result.
+    pub fn into_result(self) -> io::Result<Vec<(K, T)>> {
+        self.results
+            .into_iter()
+            .map(|(key, result)| result.map(|x| (key, x)))
+            .collect()
+    }
+}
+
+struct State<K, T> {
+    queue: std::vec::IntoIter<(usize, K)>,
+    results: Vec<(usize, K, io::Result<T>)>,
+    in_flight: Vec<(usize, K)>,
+    failed: bool,
+}
+
+impl<K: Clone, T> State<K, T> {
+    fn next(&mut self, on_error: OnError) -> Option<(usize, K)> {
+        if self.failed && on_error == OnError::Cancel {
+            return None;
+        }
+        let (i, key) = self.queue.next()?;
+        self.in_flight.push((i, key.clone()));
+        Some((i, key))
+    }
+
+    fn finish(&mut self, i: usize, key: K, result: io::Result<T>) {
+        self.in_flight.retain(|(j, _)| *j != i);
+        self.failed |= result.is_err();
+        self.results.push((i, key, result))
+    }
+}
+
+/// Pooled connection poisoned when dropped in the middle of an operation.
+struct Checkout<'a> {
+    conn: Object<Manager<'a>>,
+    busy: bool,
+}
+
+impl Drop for Checkout<'_> {
+    fn drop(&mut self) {
+        if self.busy {
+            self.conn.poisoned = Some("operation cancelled by for_each_key".to_string())
+        }
+    }
+}
+
+async fn worker<'a, K: Clone, T>(
+    pool: &Pool<'a>,
+    state: &Mutex<State<K, T>>,
+    on_error: OnError,
+    op: &impl AsyncFn(&mut Connection, &K) -> io::Result<T>,
+) {
+    let mut checkout: Option<Checkout<'a>> = None;
+    loop {
+        let Some((i, key)) = state.lock().unwrap().next(on_error) else {
+            return;
+        };
+        if checkout.is_none() {
+            match pool.get().await {
+                Ok(conn) => checkout = Some(Checkout { conn, busy: false }),
+                Err(e) => {
+                    state.lock().unwrap().finish(i, key, Err(pool_error(e)));
+                    continue;
+                }
+            }
+        }
+        let c = checkout.as_mut().unwrap();
+        c.busy = true;
+        let result = op(&mut c.conn, &key).await;
+        c.busy = false;
+        state.lock().unwrap().finish(i, key, result)
+    }
+}
+
+/// Runs `op` on every key with at most `options.concurrency`
+/// operations in flight, each on its own pooled connection.
+///
+/// With [OnError::Cancel] the first failure stops the run, operations
+/// in flight are dropped and keys not finished end up in
+/// [Outcome::cancelled].
+///
+/// # Example
+///
+/// ```
+/// use mcmc_rs::{AddrArg, Manager, Pool, concurrent};
+/// # use smol::{io, block_on};
+/// #
+/// # block_on(async {
+/// let pool = Pool::builder(Manager::new(AddrArg::Tcp("127.0.0.1:11211")))
+///     .build()
+///     .unwrap();
+/// let keys = (0..100).map(|i| format!("key{i}"));
+/// let outcome = concurrent::for_each_key(&pool, keys, 8, async |conn, key| {
+///     conn.set(key, 0, 0, false, b"warm").await
+/// })
+/// .await;
+/// assert_eq!(outcome.into_result()?.len(), 100);
+/// #     Ok::<(), io::Error>(())
+/// # }).unwrap()
+/// ```
+pub async fn for_each_key<K: Clone, T>(
+    pool: &Pool<'_>,
+    keys: impl IntoIterator<Item = K>,
+    options: impl Into<Options>,
+    op: impl AsyncFn(&mut Connection, &K) -> io::Result<T>,
+) -> Outcome<K, T> {
+    let options = options.into();
+    let concurrency = options.concurrency.min(pool.status().max_size).max(1);
+    let state = Mutex::new(State {
+        queue: keys.into_iter().enumerate().collect::<Vec<_>>().into_iter(),
+        results: Vec::new(),
+        in_flight: Vec::new(),
+        failed: false,
+    });
+    let mut workers: Vec<_> = (0..concurrency)
+        .map(|_| Some(Box::pin(worker(pool, &state, options.on_error, &op))))
+        .collect();
+    poll_fn(|cx| {
+        let mut pending = false;
+        for slot in workers.iter_mut() {
+            if let Some(w) = slot {
+                match Pin::as_mut(w).poll(cx) {
+                    Poll::Ready(()) => *slot = None,
+                    Poll::Pending => pending = true,
+                }
+            }
+        }
+        let cancel = options.on_error == OnError::Cancel && state.lock().unwrap().failed;
+        if pending && !cancel {
+            Poll::Pending
+        } else {
+            Poll::Ready(())
+        }
+    })
+    .await;
+    drop(workers);
+    let state = state.into_inner().unwrap();
+    let mut results = state.results;
+    results.sort_by_key(|(i, _, _)| *i);
+    let mut cancelled: Vec<_> = state.in_flight.into_iter().chain(state.queue).collect();
+    cancelled.sort_by_key(|(i, _)| *i);
+    Outcome {
+        results: results.into_iter().map(|(_, k, r)| (k, r)).collect(),
+        cancelled: cancelled.into_iter().map(|(_, k)| k).collect(),
+    }
+}
@@ -0,0 +1,400 @@
+//! Dual reads for migrating between clusters.
+//!
+//! Before switching to a new cluster its contents are checked against
+//! the old one under real traffic. A [DualReader] serves every read
+//! from the primary and repeats it on the shadow, comparing the values
+//! and counting matches, mismatches, shadow errors and how much slower
+//! the shadow was. By default the shadow read runs in a background
+//! task after the primary replied, so it adds no latency to callers.
+//! At most [Policy::max_in_flight] of those tasks run at once, reads
+//! past that skip the shadow.
+
+use std::fmt;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::Duration;
+
+use crate::rt::{io, spawn_detached};
+use crate::{Client, Clock, Item, SharedConnection, now};
+
+/// Backends a [DualReader] reads and writes through.
+pub trait Backend: Clone + Send + Sync + 'static {
+    fn get(&self, key: &[u8]) -> impl Future<Output = io::Result<Option<Item>>> + Send;
+    fn set(
+        &self,
+        key: &[u8],
+        exptime: i64,
+        data_block: &[u8],
+    ) -> impl Future<Output = io::Result<bool>> + Send;
+    fn delete(&self, key: &[u8]) -> impl Future<Output = io::Result<bool>> + Send;
+}
+
+impl Backend for SharedConnection {
+    async fn get(&self, key: &[u8]) -> io::Result<Option<Item>> {
+        SharedConnection::get(self, key).await
+    }
+
+    async fn set(&self, key: &[u8], exptime: i64, data_block: &[u8]) -> io::Result<bool> {
+        Ok(
+            SharedConnection::set(self, key, 0, exptime, false, data_block)
+                .await?
+                .reply()
+                .unwrap_or(false),
+        )
+    }
+
+    async fn delete(&self, key: &[u8]) -> io::Result<bool> {
+        Ok(SharedConnection::delete(self, key, false)
+            .await?
+            .reply()
+            .unwrap_or(false))
+    }
+}
+
+impl Backend for Client {
+    async fn get(&self, key: &[u8]) -> io::Result<Option<Item>> {
+        Client::get(self, key).await
+    }
+
+    async fn set(&self, key: &[u8], exptime: i64, data_block: &[u8]) -> io::Result<bool> {
+        Ok(Client::set(self, key, 0, exptime, false, data_block)
+            .await?
+            .reply()
+            .unwrap_or(false))
+    }
+
+    async fn delete(&self, key: &[u8]) -> io::Result<bool> {
+        Ok(Client::delete(self, key, false)
+            .await?
+            .reply()
+            .unwrap_or(false))
+    }
+}
+
+/// Where the shadow side of a [DualReader] runs.
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub enum ShadowMode {
+    /// In a background task started after the primary replied.
+    #[default]
+    Background,
+    /// Before the call returns, the caller waits for the shadow too.
+    Inline,
+}
+
+/// User hash of a value, see [Compare::Hash].
+pub type ValueHash = Arc<dyn Fn(&[u8]) -> u64 + Send + Sync>;
+
+/// How a [DualReader] decides two values match.
+#[derive(Clone, Default)]
+pub enum Compare {
+    /// Equal bytes.
+    #[default]
+    Bytes,
+    /// Equal hashes, for values whose encoding differs between the
+    /// clusters but hash to the same thing after decoding.
+    Hash(ValueHash),
+}
+
+impl Compare {
+    fn eq(&self, a: &[u8], b: &[u8]) -> bool {
+        match self {
+            Compare::Bytes => a == b,
+            Compare::Hash(hash) => hash(a) == hash(b),
+        }
+    }
+}
+
+impl fmt::Debug for Compare {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Compare::Bytes => f.write_str("Bytes"),
+            Compare::Hash(_) => f.write_str("Hash(..)"),
+        }
+    }
+}
+
+/// Shadow reads a [DualReader] runs at once in the background by
+/// default.
+pub const DEFAULT_MAX_IN_FLIGHT: usize = 64;
+
+/// Settings of a [DualReader].
+#[derive(Debug, Clone)]
+pub struct Policy {
+    pub mode: ShadowMode,
+    pub compare: Compare,
+    /// Also apply sets and deletes to the shadow.
+    pub mirror_writes: bool,
+    /// Background shadow reads running at once, a read arriving when
+    /// the limit is reached skips the shadow and counts in
+    /// [DualStats::shadow_skipped]. Not used with [ShadowMode::Inline].
+    pub max_in_flight: usize,
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        Self {
+            mode: ShadowMode::default(),
+            compare: Compare::default(),
+            mirror_writes: false,
+            max_in_flight: DEFAULT_MAX_IN_FLIGHT,
+        }
+    }
+}
+
+/// A read whose shadow value differs from the primary one, `None`
+/// stands for a miss.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Mismatch {
+    pub key: Vec<u8>,
+    pub primary: Option<Vec<u8>>,
+    pub shadow: Option<Vec<u8>>,
+}
+
+/// Counters of a [DualReader].
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub struct DualStats {
+    pub matches: u64,
+    pub mismatches: u64,
+    /// Failed shadow reads and mirrored writes.
+    pub shadow_errors: u64,
+    /// Reads whose shadow was skipped, see [Policy::max_in_flight].
+    pub shadow_skipped: u64,
+    /// Sum over reads of how much longer the shadow took than the
+    /// primary, reads where the shadow was faster add nothing.
+    pub shadow_slower_by: Duration,
+}
+
+#[derive(Default)]
+struct Counters {
+    matches: AtomicU64,
+    mismatches: AtomicU64,
+    shadow_errors: AtomicU64,
+    shadow_skipped: AtomicU64,
+    shadow_slower_by_nanos: AtomicU64,
+    in_flight: AtomicUsize,
+}
+
+/// Holds a slot of [Policy::max_in_flight] until dropped, also when
+/// the background task is cancelled.
+struct InFlight(Arc<Counters>);
+
+impl InFlight {
+    fn take(counters: &Arc<Counters>, max: usize) -> Option<Self> {
+        counters
+            .in_flight
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |x| {
+                (x < max).then_some(x + 1)
+            })
+            .ok()
+            .map(|_| Self(counters.clone()))
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.0.in_flight.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+type OnMismatch = Arc<dyn Fn(&Mismatch) + Send + Sync>;
+
+/// Serves from `primary` and checks `shadow` against it, see the
+/// [module docs](self). Clones share their counters.
+///
+/// # Example
+///
+/// ```
+/// use mcmc_rs::dual::{DualReader, Policy};
+/// use mcmc_rs::{Connection, SharedConnection};
+/// # use smol::{io, block_on};
+/// #
+/// # block_on(async {
+/// let old = SharedConnection::new(Connection::default().await?);
+/// let new = SharedConnection::new(Connection::tcp_connect("127.0.0.1:11212").await?);
+/// let reader = DualReader::new(old, new, Policy::default())
+///     .on_mismatch(|m| eprintln!("mismatch on {:?}", m.key));
+/// let item = reader.get(b"key").await?;
+/// println!("{item:?} {:?}", reader.stats());
+/// #     Ok::<(), io::Error>(())
+/// # }).unwrap()
+/// ```
+#[derive(Clone)]
+pub struct DualReader<P, S> {
+    primary: P,
+    shadow: S,
+    policy: Policy,
+    on_mismatch: Option<OnMismatch>,
+    clock: Option<Arc<dyn Clock>>,
+    counters: Arc<Counters>,
+}
+
+impl<P: Backend, S: Backend> DualReader<P, S> {
+    pub fn new(primary: P, shadow: S, policy: Policy) -> Self {
+        Self {
+            primary,
+            shadow,
+            policy,
+            on_mismatch: None,
+            clock: None,
+            counters: Arc::default(),
+        }
+    }
+
+    /// Replaces the time source of [DualStats::shadow_slower_by],
+    /// e.g. in tests.
+    pub fn clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = Some(clock);
+        self
+    }
+
+    /// Called with every mismatch, from the background task unless
+    /// [ShadowMode::Inline] is set.
+    pub fn on_mismatch(mut self, f: impl Fn(&Mismatch) + Send + Sync + 'static) -> Self {
+        self.on_mismatch = Some(Arc::new(f));
+        self
+    }
+
+    pub fn stats(&self) -> DualStats {
+        let c = &self.counters;
+        DualStats {
+            matches: c.matches.load(Ordering::Relaxed),
+            mismatches: c.mismatches.load(Ordering::Relaxed),
+            shadow_errors: c.shadow_errors.load(Ordering::Relaxed),
+            shadow_skipped: c.shadow_skipped.load(Ordering::Relaxed),
+            shadow_slower_by: Duration::from_nanos(
+                c.shadow_slower_by_nanos.load(Ordering::Relaxed),
+            ),
+        }
+    }
+
+    /// Reads `key` from the primary and returns its result, the shadow
+    /// read is compared against it and never changes what is returned.
+    /// Primary errors are returned without reading the shadow.
+    pub async fn get(&self, key: impl AsRef<[u8]>) -> io::Result<Option<Item>> {
+        let key = key.as_ref();
+        let started = now(&self.clock);
+        let item = self.primary.get(key).await?;
+        let primary_took = now(&self.clock).saturating_duration_since(started);
+        let check = Check {
+            shadow: self.shadow.clone(),
+            compare: self.policy.compare.clone(),
+            on_mismatch: self.on_mismatch.clone(),
+            clock: self.clock.clone(),
+            counters: self.counters.clone(),
+            key: key.to_vec(),
+            primary: item.as_ref().map(|x| x.data_block.clone()),
+            primary_took,
+        };
+        match self.policy.mode {
+            ShadowMode::Background => {
+                match InFlight::take(&self.counters, self.policy.max_in_flight) {
+                    Some(slot) => spawn_detached(check.run(Some(slot))),
+                    None => {
+                        self.counters.shadow_skipped.fetch_add(1, Ordering::Relaxed);
+                    }
+                }
+            }
+            ShadowMode::Inline => check.run(None).await,
+        }
+        Ok(item)
+    }
+
+    /// Writes to the primary and, with [Policy::mirror_writes], to the
+    /// shadow. A failed shadow write only counts as a shadow error.
+    pub async fn set(
+        &self,
+        key: impl AsRef<[u8]>,
+        exptime: i64,
+        data_block: impl AsRef<[u8]>,
+    ) -> io::Result<bool> {
+        let (key, data_block) = (key.as_ref(), data_block.as_ref());
+        let stored = self.primary.set(key, exptime, data_block).await?;
+        if self.policy.mirror_writes {
+            let (shadow, counters) = (self.shadow.clone(), self.counters.clone());
+            let (key, data_block) = (key.to_vec(), data_block.to_vec());
+            self.mirror(async move {
+                if shadow.set(&key, exptime, &data_block).await.is_err() {
+                    counters.shadow_errors.fetch_add(1, Ordering::Relaxed);
+                }
+            })
+            .await
+        }
+        Ok(stored)
+    }
+
+    /// Deletes from the primary and, with [Policy::mirror_writes], from
+    /// the shadow. A failed shadow delete only counts as a shadow error.
+    pub async fn delete(&self, key: impl AsRef<[u8]>) -> io::Result<bool> {
+        let key = key.as_ref();
+        let deleted = self.primary.delete(key).await?;
+        if self.policy.mirror_writes {
+            let (shadow, counters) = (self.shadow.clone(), self.counters.clone());
+            let key = key.to_vec();
+            self.mirror(async move {
+                if shadow.delete(&key).await.is_err() {
+                    counters.shadow_errors.fetch_add(1, Ordering::Relaxed);
+                }
+            })
+            .await
+        }
+        Ok(deleted)
+    }
+
+    async fn mirror(&self, fut: impl Future<Output = ()> + Send + 'static) {
+        match self.policy.mode {
+            ShadowMode::Background => spawn_detached(fut),
+            ShadowMode::Inline => fut.await,
+        }
+    }
+}
+
+/// The shadow half of a [DualReader::get].
+struct Check<S> {
+    shadow: S,
+    compare: Compare,
+    on_mismatch: Option<OnMismatch>,
+    clock: Option<Arc<dyn Clock>>,
+    counters: Arc<Counters>,
+    key: Vec<u8>,
+    primary: Option<Vec<u8>>,
+    primary_took: Duration,
+}
+
+impl<S: Backend> Check<S> {
+    /// Reads the shadow, `slot` is given back as soon as it replied.
+    async fn run(self, slot: Option<InFlight>) {
+        let started = now(&self.clock);
+        let result = self.shadow.get(&self.key).await;
+        drop(slot);
+        let slower_by = now(&self.clock)
+            .saturating_duration_since(started)
+            .saturating_sub(self.primary_took);
+        let c = &self.counters;
+        c.shadow_slower_by_nanos
+            .fetch_add(slower_by.as_nanos() as u64, Ordering::Relaxed);
+        let shadow = match result {
+            Ok(item) => item.map(|x| x.data_block),
+            Err(_) => {
+                c.shadow_errors.fetch_add(1, Ordering::Relaxed);
+                return;
+            }
+        };
+        let same = match (&self.primary, &shadow) {
+            (Some(a), Some(b)) => self.compare.eq(a, b),
+            (None, None) => true,
+            _ => false,
+        };
+        if same {
+            c.matches.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+        c.mismatches.fetch_add(1, Ordering::Relaxed);
+        if let Some(f) = &self.on_mismatch {
+            f(&Mismatch {
+                key: self.key,
+                primary: self.primary,
+                shadow,
+            })
+        }
+    }
+}
@@ -0,0 +1,121 @@
+//! Background pings for long-lived idle connections.
+//!
+//! Load balancers and NATs silently drop connections idle for
+//! 60–350 seconds, the next request then pays for a reconnect.
+//! [spawn] sends `mn` on connections idle for at least the given
+//! interval, connections whose ping fails are poisoned so a [Pool]
+//! recreates them on the next checkout.
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use deadpool::managed::{Object, Timeouts};
+
+use crate::rt::{sleep, spawn_detached, try_lock};
+use crate::{Connection, Pool, SharedConnection};
+
+/// Connections a keepalive task can ping, see [spawn].
+pub trait Target: Clone + Send + Sync + 'static {
+    /// Pings every connection idle for at least `interval`.
+    fn ping_idle(&self, interval: Duration) -> impl Future<Output = ()> + Send;
+}
+
+/// A locked connection is in use, so it is skipped.
+impl Target for SharedConnection {
+    async fn ping_idle(&self, interval: Duration) {
+        if let Some(mut conn) = try_lock(&self.0) {
+            ping(&mut conn, interval).await
+        }
+    }
+}
+
+/// Pooled connections are looked at in place, and only while one of
+/// them is due one is checked out at a time, so the pool is never
+/// drained. A checkout runs the recycle probe, which counts as a ping.
+/// With a LIFO queue the checkouts stop at the first connection seen
+/// twice.
+impl Target for Pool<'static> {
+    async fn ping_idle(&self, interval: Duration) {
+        let timeouts = Timeouts {
+            wait: Some(Duration::ZERO),
+            create: None,
+            recycle: None,
+        };
+        let mut seen = Vec::new();
+        while due(self, interval) {
+            let Ok(mut conn) = self.timeout_get(&timeouts).await else {
+                break;
+            };
+            let id = Object::id(&conn);
+            if seen.contains(&id) {
+                break;
+            }
+            seen.push(id);
+            ping(&mut conn, interval).await
+        }
+    }
+}
+
+/// Whether an idle connection of `pool` waits for a ping.
+fn due(pool: &Pool<'static>, interval: Duration) -> bool {
+    let mut due = false;
+    pool.retain(|conn, _| {
+        due |= !conn.is_poisoned() && conn.idle_time() >= interval;
+        true
+    });
+    due
+}
+
+async fn ping(conn: &mut Connection, interval: Duration) {
+    if conn.is_poisoned() || conn.idle_time() < interval {
+        return;
+    }
+    if let Err(e) = conn.mn().await {
+        conn.poisoned = Some(format!("keepalive ping failed: {e}"))
+    }
+}
+
+/// Stops the keepalive task when dropped, a ping in flight is
+/// completed first so the connection is left in a clean state.
+pub struct KeepaliveGuard(Arc<AtomicBool>);
+
+impl Drop for KeepaliveGuard {
+    fn drop(&mut self) {
+        self.0.store(true, Ordering::Relaxed)
+    }
+}
+
+/// Checks `target` twice per `interval` and pings connections idle
+/// for at least `interval`, busy connections are skipped.
+///
+/// # Example
+///
+/// ```
+/// use std::time::Duration;
+///
+/// use mcmc_rs::{Connection, SharedConnection, keepalive};
+/// # use smol::{io, block_on};
+/// #
+/// # block_on(async {
+/// let shared = SharedConnection::new(Connection::default().await?);
+/// let guard = keepalive::spawn(shared.clone(), Duration::from_secs(30));
+/// shared.version().await?;
+/// drop(guard);
+/// #     Ok::<(), io::Error>(())
+/// # }).unwrap()
+/// ```
+pub fn spawn(target: impl Target, interval: Duration) -> KeepaliveGuard {
+    let stopped = Arc::new(AtomicBool::new(false));
+    let flag = stopped.clone();
+    spawn_detached(async move {
+        loop {
+            sleep(interval / 2).await;
+            if flag.load(Ordering::Relaxed) {
+                break;
+            }
+            target.ping_idle(interval).await
+        }
+    });
+    KeepaliveGuard(stopped)
+}
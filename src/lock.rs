@@ -0,0 +1,180 @@
+//! Best-effort distributed locks on top of `add`.
+//!
+//! [Lock::acquire] stores a random owner token under the lock key with
+//! `add`, which only one caller can win until the key expires or is
+//! deleted. The TTL bounds how long a crashed owner blocks others, and
+//! [LockGuard::release] deletes the key only while it still holds the
+//! token, so an owner whose lock expired cannot release the next one.
+//!
+//! Memcached may evict the key or lose it on restart, the lock is a
+//! way to avoid duplicate work, not a guarantee of mutual exclusion.
+
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::rt::{io, sleep, try_spawn_detached};
+use crate::{Acked, MdFlag, MgFlag, SharedConnection, now};
+
+const BACKOFF_MIN: Duration = Duration::from_millis(10);
+
+const BACKOFF_MAX: Duration = Duration::from_millis(500);
+
+/// Entry point of the lock, see [Lock::acquire].
+#[derive(Debug)]
+pub struct Lock;
+
+impl Lock {
+    /// Takes the lock `key` for `ttl` seconds. Without `wait` a single
+    /// `add` is tried, with it the `add` is retried with exponential
+    /// backoff until the wait budget is spent. Returns `None` when the
+    /// lock is held by someone else. A `ttl` of 0 or less is refused
+    /// with [io::ErrorKind::InvalidInput], since the key would never
+    /// expire or expire at once.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use mcmc_rs::{Connection, SharedConnection, lock::Lock};
+    /// # use std::time::Duration;
+    /// # use smol::{io, block_on};
+    /// #
+    /// # block_on(async {
+    /// let conn = SharedConnection::new(Connection::default().await?);
+    /// let wait = Some(Duration::from_secs(1));
+    /// if let Some(guard) = Lock::acquire(&conn, b"lock:report", 30, wait).await? {
+    ///     // ... build the report ...
+    ///     guard.release().await?;
+    /// }
+    /// #     Ok::<(), io::Error>(())
+    /// # }).unwrap()
+    /// ```
+    pub async fn acquire(
+        conn: &SharedConnection,
+        key: impl AsRef<[u8]>,
+        ttl: i64,
+        wait: Option<Duration>,
+    ) -> io::Result<Option<LockGuard>> {
+        if ttl <= 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("lock ttl must be positive, got {ttl}"),
+            ));
+        }
+        let key = key.as_ref();
+        let token = owner_token();
+        let clock = conn.lock().await.clock.clone();
+        let deadline = wait.map(|x| now(&clock) + x);
+        let mut backoff = BACKOFF_MIN;
+        loop {
+            if conn.add(key, 0, ttl, false, &token).await? == Acked::Reply(true) {
+                return Ok(Some(LockGuard {
+                    conn: conn.clone(),
+                    key: key.to_vec(),
+                    token,
+                    released: false,
+                }));
+            }
+            let left = match deadline {
+                Some(x) => x.saturating_duration_since(now(&clock)),
+                None => Duration::ZERO,
+            };
+            if left.is_zero() {
+                return Ok(None);
+            }
+            sleep(backoff.min(left)).await;
+            backoff = (backoff * 2).min(BACKOFF_MAX);
+        }
+    }
+}
+
+/// A held lock, see [Lock::acquire]. Dropping it without
+/// [LockGuard::release] releases it in a background task. Outside a
+/// tokio runtime nothing can be spawned, the lock is then left to
+/// expire with its TTL.
+pub struct LockGuard {
+    conn: SharedConnection,
+    key: Vec<u8>,
+    token: String,
+    released: bool,
+}
+
+impl LockGuard {
+    /// The lock key.
+    pub fn key(&self) -> &[u8] {
+        &self.key
+    }
+
+    /// The random value stored under the lock key.
+    pub fn token(&self) -> &str {
+        &self.token
+    }
+
+    /// Deletes the lock key if it still holds this guard's token,
+    /// compared by cas so a lock taken over in between is left alone.
+    /// Returns `false` when the lock had already expired or changed
+    /// owner.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use mcmc_rs::{Connection, SharedConnection, lock::Lock};
+    /// # use smol::{io, block_on};
+    /// #
+    /// # block_on(async {
+    /// let conn = SharedConnection::new(Connection::default().await?);
+    /// let guard = Lock::acquire(&conn, b"lock:job", 30, None).await?.unwrap();
+    /// assert!(guard.release().await?);
+    /// #     Ok::<(), io::Error>(())
+    /// # }).unwrap()
+    /// ```
+    pub async fn release(mut self) -> io::Result<bool> {
+        self.released = true;
+        release(&self.conn, &self.key, &self.token).await
+    }
+}
+
+impl Drop for LockGuard {
+    fn drop(&mut self) {
+        if self.released {
+            return;
+        }
+        let (conn, key, token) = (
+            self.conn.clone(),
+            std::mem::take(&mut self.key),
+            std::mem::take(&mut self.token),
+        );
+        try_spawn_detached(async move {
+            let _ = release(&conn, &key, &token).await;
+        });
+    }
+}
+
+async fn release(conn: &SharedConnection, key: &[u8], token: &str) -> io::Result<bool> {
+    let item = conn
+        .mg(key, &[MgFlag::ReturnValue, MgFlag::ReturnCas])
+        .await?;
+    match (item.data_block, item.cas) {
+        (Some(value), Some(cas)) if item.success && value == token.as_bytes() => {
+            Ok(conn.md(key, &[MdFlag::CompareCas(cas)]).await?.success)
+        }
+        _ => Ok(false),
+    }
+}
+
+/// Unique per call within the process and unpredictable across
+/// processes.
+fn owner_token() -> String {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let mut h = RandomState::new().build_hasher();
+    h.write_u64(COUNTER.fetch_add(1, Ordering::Relaxed));
+    h.write_u128(
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos(),
+    );
+    h.write_u32(std::process::id());
+    format!("{:016x}", h.finish())
+}
@@ -0,0 +1,356 @@
+//! Helpers for testing code built on this crate, with the `test-util`
+//! feature.
+//!
+//! - [MockClock] is a [Clock] that only moves when told to, so TTL,
+//!   idle and age logic can be tested without sleeping.
+//! - [MockServer] is an in-memory memcached speaking a small part of
+//!   the text protocol, with [ChaosRule]s injecting latency, error
+//!   replies, corrupt replies and dropped connections.
+
+use std::collections::HashMap;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::Clock;
+use crate::rt::*;
+
+/// A [Clock] starting at the time it was created and moved forward
+/// with [MockClock::advance].
+///
+/// # Example
+///
+/// ```
+/// use std::sync::Arc;
+/// use std::time::Duration;
+///
+/// use mcmc_rs::test_util::MockClock;
+/// use mcmc_rs::{Clock, Connection};
+/// # use smol::{io, block_on};
+/// #
+/// # block_on(async {
+/// let clock = Arc::new(MockClock::new());
+/// let mut conn = Connection::default().await?;
+/// conn.set_clock(clock.clone());
+/// clock.advance(Duration::from_secs(60));
+/// assert!(conn.idle_time() >= Duration::from_secs(60));
+/// #     Ok::<(), io::Error>(())
+/// # }).unwrap()
+/// ```
+#[derive(Debug)]
+pub struct MockClock {
+    instant: Instant,
+    system: SystemTime,
+    elapsed: Mutex<Duration>,
+}
+
+impl MockClock {
+    pub fn new() -> Self {
+        Self {
+            instant: Instant::now(),
+            system: SystemTime::now(),
+            elapsed: Mutex::new(Duration::ZERO),
+        }
+    }
+
+    /// Moves both times forward by `by`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::time::Duration;
+    ///
+    /// use mcmc_rs::Clock;
+    /// use mcmc_rs::test_util::MockClock;
+    ///
+    /// let clock = MockClock::new();
+    /// let (instant, system) = (clock.now_instant(), clock.now_system());
+    /// clock.advance(Duration::from_secs(5));
+    /// assert_eq!(clock.now_instant() - instant, Duration::from_secs(5));
+    /// assert_eq!(clock.now_system(), system + Duration::from_secs(5));
+    /// ```
+    pub fn advance(&self, by: Duration) {
+        *self.elapsed.lock().unwrap() += by
+    }
+
+    /// Time moved by [MockClock::advance] so far.
+    pub fn elapsed(&self) -> Duration {
+        *self.elapsed.lock().unwrap()
+    }
+}
+
+impl Default for MockClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for MockClock {
+    fn now_instant(&self) -> Instant {
+        self.instant + self.elapsed()
+    }
+
+    fn now_system(&self) -> SystemTime {
+        self.system + self.elapsed()
+    }
+}
+
+/// What a [ChaosRule] does to a reply.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Fault {
+    /// Waits before replying.
+    Latency(Duration),
+    /// Waits a random time between the two durations before replying.
+    RandomLatency(Duration, Duration),
+    /// Replies `SERVER_ERROR chaos` instead, nothing is stored.
+    ServerError,
+    /// Advertises one byte more in `VALUE` lines than the data block
+    /// holds.
+    CorruptLength,
+    /// Writes the first half of the reply and closes the connection.
+    PartialWrite,
+    /// Closes the connection after replying.
+    Disconnect,
+}
+
+/// A [Fault] injected by a [MockServer], see [MockServer::chaos].
+#[derive(Debug, PartialEq, Clone)]
+pub struct ChaosRule {
+    /// Command name like `get`, `None` matches every command.
+    pub command: Option<String>,
+    /// Fires only on the n-th matching request, counted from 1 over
+    /// all connections, `None` fires on every one.
+    pub nth: Option<u64>,
+    pub fault: Fault,
+}
+
+struct Rule {
+    rule: ChaosRule,
+    seen: u64,
+    fired: u64,
+}
+
+/// Flags and value per key.
+type Items = HashMap<Vec<u8>, (u32, Vec<u8>)>;
+
+#[derive(Default)]
+struct State {
+    items: Mutex<Items>,
+    rules: Mutex<Vec<Rule>>,
+}
+
+impl State {
+    /// Counts the request against every rule and returns the faults
+    /// firing on it.
+    fn faults(&self, command: &str) -> Vec<Fault> {
+        let mut faults = Vec::new();
+        for x in self.rules.lock().unwrap().iter_mut() {
+            if x.rule.command.as_ref().is_some_and(|c| c != command) {
+                continue;
+            }
+            x.seen += 1;
+            if x.rule.nth.is_none_or(|n| n == x.seen) {
+                x.fired += 1;
+                faults.push(x.rule.fault)
+            }
+        }
+        faults
+    }
+}
+
+/// An in-memory server for tests, answering `get`, `gets`, `set`,
+/// `add`, `delete`, `version`, `mn` and `flush_all` on a local TCP
+/// port. Other commands get `ERROR`, flags are kept and `exptime` is
+/// ignored. Dropping it stops the server and closes its connections.
+///
+/// # Example
+///
+/// ```
+/// use mcmc_rs::Connection;
+/// use mcmc_rs::test_util::{ChaosRule, Fault, MockServer};
+/// # use smol::{io, block_on};
+/// #
+/// # block_on(async {
+/// let server = MockServer::start().await?;
+/// server.chaos(ChaosRule {
+///     command: Some("get".to_string()),
+///     nth: Some(2),
+///     fault: Fault::ServerError,
+/// });
+/// let mut conn = Connection::tcp_connect(&server.addr()).await?;
+/// conn.set(b"key", 0, 0, false, b"value").await?;
+/// assert!(conn.get(b"key").await?.is_some());
+/// assert!(conn.get(b"key").await.is_err());
+/// assert_eq!(server.fired(), [1]);
+/// #     Ok::<(), io::Error>(())
+/// # }).unwrap()
+/// ```
+pub struct MockServer {
+    addr: SocketAddr,
+    state: Arc<State>,
+    _task: Spawned,
+}
+
+impl MockServer {
+    /// Listens on a free port of `127.0.0.1`.
+    pub async fn start() -> io::Result<Self> {
+        Self::bind("127.0.0.1:0").await
+    }
+
+    /// Listens on `addr`, e.g. to restart a server on the same port.
+    pub async fn bind(addr: &str) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr).await?;
+        let addr = listener.local_addr()?;
+        let state = Arc::new(State::default());
+        let shared = state.clone();
+        let task = spawn_cancellable(async move {
+            let mut conns = Vec::new();
+            while let Ok((s, _)) = listener.accept().await {
+                conns.push(spawn_cancellable(serve(s, shared.clone())))
+            }
+        });
+        Ok(Self {
+            addr,
+            state,
+            _task: task,
+        })
+    }
+
+    /// `host:port` to connect to.
+    pub fn addr(&self) -> String {
+        self.addr.to_string()
+    }
+
+    /// Adds a rule, every rule matching a request fires in the order
+    /// they were added.
+    pub fn chaos(&self, rule: ChaosRule) {
+        self.state.rules.lock().unwrap().push(Rule {
+            rule,
+            seen: 0,
+            fired: 0,
+        })
+    }
+
+    /// Removes every rule and its counter.
+    pub fn clear_chaos(&self) {
+        self.state.rules.lock().unwrap().clear()
+    }
+
+    /// How many times each rule fired, in the order they were added.
+    pub fn fired(&self) -> Vec<u64> {
+        self.state
+            .rules
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|x| x.fired)
+            .collect()
+    }
+}
+
+fn random_between(min: Duration, max: Duration) -> Duration {
+    let span = max.saturating_sub(min);
+    let fraction = RandomState::new().build_hasher().finish() as f64 / u64::MAX as f64;
+    min + span.mul_f64(fraction)
+}
+
+async fn serve(s: TcpStream, state: Arc<State>) {
+    let mut s = BufReader::new(s);
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if s.read_line(&mut line).await.unwrap_or(0) == 0 {
+            return;
+        }
+        let args: Vec<&str> = line.split_whitespace().collect();
+        let Some(&command) = args.first() else {
+            continue;
+        };
+        let mut data = None;
+        if matches!(command, "set" | "add") {
+            let Some(bytes) = args.get(4).and_then(|x| x.parse::<usize>().ok()) else {
+                return;
+            };
+            let mut block = vec![0; bytes + 2];
+            if s.read_exact(&mut block).await.is_err() {
+                return;
+            }
+            block.truncate(bytes);
+            data = Some(block)
+        }
+        let faults = state.faults(command);
+        let mut reply = if faults.contains(&Fault::ServerError) {
+            b"SERVER_ERROR chaos\r\n".to_vec()
+        } else {
+            let corrupt = faults.contains(&Fault::CorruptLength);
+            execute(&state, &args, data, corrupt)
+        };
+        if args.last() == Some(&"noreply") && command != "get" && command != "gets" {
+            reply.clear()
+        }
+        for fault in &faults {
+            match *fault {
+                Fault::Latency(d) => sleep(d).await,
+                Fault::RandomLatency(min, max) => sleep(random_between(min, max)).await,
+                _ => (),
+            }
+        }
+        let w = s.get_mut();
+        if faults.contains(&Fault::PartialWrite) {
+            let _ = w.write_all(&reply[..reply.len() / 2]).await;
+            let _ = w.flush().await;
+            return;
+        }
+        if w.write_all(&reply).await.is_err() || w.flush().await.is_err() {
+            return;
+        }
+        if faults.contains(&Fault::Disconnect) {
+            return;
+        }
+    }
+}
+
+fn execute(state: &State, args: &[&str], data: Option<Vec<u8>>, corrupt: bool) -> Vec<u8> {
+    let mut items = state.items.lock().unwrap();
+    match (args[0], data) {
+        ("get" | "gets", _) => {
+            let mut reply = Vec::new();
+            for key in &args[1..] {
+                if let Some((flags, value)) = items.get(key.as_bytes()) {
+                    let bytes = value.len() + usize::from(corrupt);
+                    reply.extend(format!("VALUE {key} {flags} {bytes}").bytes());
+                    if args[0] == "gets" {
+                        reply.extend(b" 1")
+                    }
+                    reply.extend(b"\r\n");
+                    reply.extend(value);
+                    reply.extend(b"\r\n")
+                }
+            }
+            reply.extend(b"END\r\n");
+            reply
+        }
+        ("set" | "add", Some(value)) => {
+            let key = args[1].as_bytes();
+            if args[0] == "add" && items.contains_key(key) {
+                return b"NOT_STORED\r\n".to_vec();
+            }
+            let flags = args[2].parse().unwrap_or(0);
+            items.insert(key.to_vec(), (flags, value));
+            b"STORED\r\n".to_vec()
+        }
+        ("delete", _) if args.len() > 1 => match items.remove(args[1].as_bytes()) {
+            Some(_) => b"DELETED\r\n".to_vec(),
+            None => b"NOT_FOUND\r\n".to_vec(),
+        },
+        ("version", _) => b"VERSION 1.6.38-mock\r\n".to_vec(),
+        ("mn", _) => b"MN\r\n".to_vec(),
+        ("flush_all", _) => {
+            items.clear();
+            b"OK\r\n".to_vec()
+        }
+        _ => b"ERROR\r\n".to_vec(),
+    }
+}
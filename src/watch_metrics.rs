@@ -0,0 +1,183 @@
+//! Eviction metrics from a `watch evictions` stream.
+//!
+//! Eviction spikes mean the cache is too small for its working set.
+//! [bridge] reads eviction log lines of a [WatchStream] in a
+//! background task and hands the parsed [Eviction]s to an
+//! [EvictionObserver]. [EvictionStats] is the observer counting
+//! evictions per slab class and keeping the most recently evicted keys.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::{Arc, Mutex};
+
+use crate::WatchStream;
+use crate::rt::{Spawned, spawn_cancellable};
+use crate::uri_decode;
+
+/// An eviction line of the watch stream, e.g.
+/// `ts=1745299782.467270 gid=5 type=eviction key=foo fetch=no ttl=-1 la=3 clsid=1`.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Eviction {
+    pub key: Vec<u8>,
+    /// Slab class the item was evicted from.
+    pub class_id: u32,
+    /// The item was read at least once before it was evicted.
+    pub fetched: bool,
+    /// Seconds left to live, `-1` without expiration.
+    pub ttl: i64,
+    /// Seconds since the last access.
+    pub last_access: u64,
+}
+
+impl Eviction {
+    /// Parses an eviction line, `None` for other events.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use mcmc_rs::watch_metrics::Eviction;
+    ///
+    /// let line = "ts=1.5 gid=5 type=eviction key=a%20b fetch=yes ttl=-1 la=3 clsid=1";
+    /// let e = Eviction::parse(line).unwrap();
+    /// assert_eq!((e.key.as_slice(), e.class_id, e.fetched), (b"a b".as_slice(), 1, true));
+    /// assert!(Eviction::parse("ts=1.5 gid=6 type=item_get key=a status=not_found").is_none());
+    /// ```
+    pub fn parse(line: &str) -> Option<Eviction> {
+        let (mut kind, mut key, mut class_id) = (None, None, None);
+        let (mut fetched, mut ttl, mut last_access) = (false, -1, 0);
+        for field in line.split(' ') {
+            match field.split_once('=') {
+                Some(("type", x)) => kind = Some(x),
+                Some(("key", x)) => key = Some(uri_decode(x)),
+                Some(("clsid", x)) => class_id = x.parse().ok(),
+                Some(("fetch", x)) => fetched = x == "yes",
+                Some(("ttl", x)) => ttl = x.parse().ok()?,
+                Some(("la", x)) => last_access = x.parse().ok()?,
+                _ => {}
+            }
+        }
+        if kind != Some("eviction") {
+            return None;
+        }
+        Some(Eviction {
+            key: key?,
+            class_id: class_id?,
+            fetched,
+            ttl,
+            last_access,
+        })
+    }
+}
+
+/// Receives the evictions read by [bridge].
+pub trait EvictionObserver: Send + Sync + 'static {
+    fn on_eviction(&self, eviction: &Eviction);
+}
+
+/// Counts evictions per slab class and keeps the last `capacity`
+/// evicted keys.
+///
+/// # Example
+///
+/// ```
+/// use mcmc_rs::watch_metrics::{Eviction, EvictionObserver, EvictionStats};
+///
+/// let stats = EvictionStats::new(1);
+/// for line in [
+///     "ts=1.0 gid=1 type=eviction key=a fetch=no ttl=-1 la=3 clsid=1",
+///     "ts=1.1 gid=2 type=eviction key=b fetch=no ttl=-1 la=3 clsid=1",
+/// ] {
+///     stats.on_eviction(&Eviction::parse(line).unwrap());
+/// }
+/// assert_eq!(stats.by_class()[&1], 2);
+/// assert_eq!(stats.recent_keys(), [b"b".to_vec()]);
+/// ```
+#[derive(Debug)]
+pub struct EvictionStats {
+    by_class: Mutex<BTreeMap<u32, u64>>,
+    recent: Mutex<VecDeque<Vec<u8>>>,
+    capacity: usize,
+}
+
+impl EvictionStats {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            by_class: Mutex::default(),
+            recent: Mutex::new(VecDeque::with_capacity(capacity)),
+            capacity,
+        }
+    }
+
+    /// Evictions per slab class.
+    pub fn by_class(&self) -> BTreeMap<u32, u64> {
+        self.by_class.lock().unwrap().clone()
+    }
+
+    pub fn total(&self) -> u64 {
+        self.by_class.lock().unwrap().values().sum()
+    }
+
+    /// Recently evicted keys, oldest first.
+    pub fn recent_keys(&self) -> Vec<Vec<u8>> {
+        self.recent.lock().unwrap().iter().cloned().collect()
+    }
+}
+
+impl EvictionObserver for EvictionStats {
+    fn on_eviction(&self, eviction: &Eviction) {
+        *self
+            .by_class
+            .lock()
+            .unwrap()
+            .entry(eviction.class_id)
+            .or_default() += 1;
+        if self.capacity == 0 {
+            return;
+        }
+        let mut recent = self.recent.lock().unwrap();
+        if recent.len() == self.capacity {
+            recent.pop_front();
+        }
+        recent.push_back(eviction.key.clone())
+    }
+}
+
+/// Stops the task started by [bridge] when dropped.
+pub struct BridgeGuard {
+    _task: Spawned,
+}
+
+/// Reads `stream` until it ends and passes every eviction to
+/// `observer`, other lines are skipped. The stream should watch
+/// [WatchArg::Evictions](crate::WatchArg::Evictions).
+///
+/// # Example
+///
+/// ```
+/// use std::sync::Arc;
+///
+/// use mcmc_rs::watch_metrics::{self, EvictionStats};
+/// use mcmc_rs::{Connection, WatchArg};
+/// # use smol::{io, block_on};
+/// #
+/// # block_on(async {
+/// let stream = Connection::default()
+///     .await?
+///     .watch(&[WatchArg::Evictions])
+///     .await?;
+/// let stats = Arc::new(EvictionStats::new(100));
+/// let guard = watch_metrics::bridge(stream, stats.clone());
+/// println!("{} evictions", stats.total());
+/// drop(guard);
+/// #     Ok::<(), io::Error>(())
+/// # }).unwrap()
+/// ```
+pub fn bridge(mut stream: WatchStream, observer: Arc<dyn EvictionObserver>) -> BridgeGuard {
+    let task = spawn_cancellable(async move {
+        while let Ok(Some(line)) = stream.message().await {
+            if let Some(eviction) = Eviction::parse(&line) {
+                observer.on_eviction(&eviction)
+            }
+        }
+    });
+    BridgeGuard { _task: task }
+}